@@ -156,9 +156,314 @@ fn py_compress(
     Ok((compressed_ids, codebook_vec, remaining_ids))
 }
 
+/// Expands a single compressed id back into the token(s) it stands for: a literal id
+/// below `initial_vocab_size` is returned as-is, otherwise it indexes `codebook_vec`
+/// row `id - initial_vocab_size`, whose entries are emitted up to the first
+/// `eot_token_id` pad (the padding `compress` writes via `resize`).
+#[inline(always)]
+fn expand_id(
+    id: usize,
+    codebook_vec: &Vec<Vec<usize>>,
+    initial_vocab_size: usize,
+    max_codebook_size: usize,
+    max_subtokens: usize,
+    eot_token_id: usize,
+) -> Vec<usize> {
+    if id < initial_vocab_size {
+        vec![id]
+    } else {
+        let row_idx = id - initial_vocab_size;
+        debug_assert!(row_idx < max_codebook_size, "codebook id out of range");
+
+        codebook_vec[row_idx]
+            .iter()
+            .take(max_subtokens)
+            .take_while(|&&sub_id| sub_id != eot_token_id)
+            .copied()
+            .collect()
+    }
+}
+
+/// Inverse of `compress`: reconstructs the original token stream from `compressed_ids`
+/// and the codebook `compress` produced alongside them.
+fn decompress(
+    compressed_ids: &Vec<usize>,
+    codebook_vec: &Vec<Vec<usize>>,
+    initial_vocab_size: usize,
+    max_codebook_size: usize,
+    max_subtokens: usize,
+    eot_token_id: usize,
+) -> Vec<usize> {
+    let mut ids: Vec<usize> = Vec::with_capacity(compressed_ids.len());
+
+    for &id in compressed_ids {
+        ids.extend(expand_id(
+            id,
+            codebook_vec,
+            initial_vocab_size,
+            max_codebook_size,
+            max_subtokens,
+            eot_token_id,
+        ));
+    }
+
+    ids
+}
+
+/// Streaming counterpart of `decompress` for expanding large `compressed_ids` buffers
+/// without materializing the whole output at once: each call consumes as much of
+/// `input` as fits into `output`, carrying any codebook entry that straddles the
+/// boundary over to the next call. Returns `(ids_consumed, tokens_written)`.
+#[derive(Default)]
+pub struct StreamDecompressor {
+    carry: Vec<usize>,
+}
+
+impl StreamDecompressor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn decompress_into(
+        &mut self,
+        input: &[usize],
+        codebook_vec: &Vec<Vec<usize>>,
+        initial_vocab_size: usize,
+        max_codebook_size: usize,
+        max_subtokens: usize,
+        eot_token_id: usize,
+        output: &mut [usize],
+    ) -> (usize, usize) {
+        let mut written = 0;
+
+        if !self.carry.is_empty() {
+            let take = self.carry.len().min(output.len());
+            output[..take].copy_from_slice(&self.carry[..take]);
+            self.carry.drain(..take);
+            written += take;
+
+            if !self.carry.is_empty() {
+                return (0, written);
+            }
+        }
+
+        let mut consumed = 0;
+        for &id in input {
+            let expansion = expand_id(
+                id,
+                codebook_vec,
+                initial_vocab_size,
+                max_codebook_size,
+                max_subtokens,
+                eot_token_id,
+            );
+            consumed += 1;
+
+            let space = output.len() - written;
+            if expansion.len() <= space {
+                output[written..written + expansion.len()].copy_from_slice(&expansion);
+                written += expansion.len();
+            } else {
+                output[written..].copy_from_slice(&expansion[..space]);
+                written += space;
+                self.carry = expansion[space..].to_vec();
+                break;
+            }
+        }
+
+        (consumed, written)
+    }
+}
+
+#[pyfunction]
+#[pyo3(name = "decompress")]
+fn py_decompress(
+    compressed_ids: Vec<usize>,
+    codebook_vec: Vec<Vec<usize>>,
+    initial_vocab_size: usize,
+    max_codebook_size: usize,
+    max_subtokens: usize,
+    eot_token_id: usize,
+) -> PyResult<Vec<usize>> {
+    Ok(decompress(
+        &compressed_ids,
+        &codebook_vec,
+        initial_vocab_size,
+        max_codebook_size,
+        max_subtokens,
+        eot_token_id,
+    ))
+}
+
+/// Python-facing handle to a `StreamDecompressor`, letting callers pull one bounded
+/// chunk of expanded tokens at a time instead of decompressing a whole file in memory.
+#[pyclass]
+struct PyStreamDecompressor {
+    inner: StreamDecompressor,
+}
+
+#[pymethods]
+impl PyStreamDecompressor {
+    #[new]
+    fn new() -> Self {
+        Self {
+            inner: StreamDecompressor::new(),
+        }
+    }
+
+    /// Decompresses as much of `compressed_ids` as fits in `output_capacity` slots,
+    /// returning `(tokens, ids_consumed)`.
+    fn decompress_chunk(
+        &mut self,
+        compressed_ids: Vec<usize>,
+        codebook_vec: Vec<Vec<usize>>,
+        initial_vocab_size: usize,
+        max_codebook_size: usize,
+        max_subtokens: usize,
+        eot_token_id: usize,
+        output_capacity: usize,
+    ) -> PyResult<(Vec<usize>, usize)> {
+        let mut output = vec![0usize; output_capacity];
+        let (consumed, written) = self.inner.decompress_into(
+            &compressed_ids,
+            &codebook_vec,
+            initial_vocab_size,
+            max_codebook_size,
+            max_subtokens,
+            eot_token_id,
+            &mut output,
+        );
+        output.truncate(written);
+
+        Ok((output, consumed))
+    }
+}
+
 #[pymodule]
 fn fast_compression(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(py_compress, m)?)?;
+    m.add_function(wrap_pyfunction!(py_decompress, m)?)?;
+    m.add_class::<PyStreamDecompressor>()?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Small deterministic PRNG so the round-trip test below doesn't need an extra
+    /// dependency just to generate input sequences.
+    struct Xorshift(u64);
+
+    impl Xorshift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn next_usize(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+    }
+
+    #[test]
+    fn decompress_inverts_compress() {
+        let initial_vocab_size = 50257;
+        let max_codebook_size = 16;
+        let max_subtokens = 4;
+        let max_out_seq_length = 10_000;
+        let eot_token_id = 50256;
+
+        let mut rng = Xorshift(0x2545F4914F6CDD1D);
+        let disabled_ids = disabled_ids_to_set(Some(vec![eot_token_id]));
+
+        for _ in 0..20 {
+            let len = 50 + rng.next_usize(500);
+            let ids: Vec<usize> = (0..len).map(|_| rng.next_usize(initial_vocab_size)).collect();
+
+            let (compressed_ids, codebook_vec, _) = compress(
+                &ids,
+                initial_vocab_size,
+                max_codebook_size,
+                max_subtokens,
+                max_out_seq_length,
+                eot_token_id,
+                &disabled_ids,
+            );
+
+            let round_tripped = decompress(
+                &compressed_ids,
+                &codebook_vec,
+                initial_vocab_size,
+                max_codebook_size,
+                max_subtokens,
+                eot_token_id,
+            );
+
+            assert_eq!(round_tripped, ids);
+        }
+    }
+
+    #[test]
+    fn stream_decompress_matches_decompress() {
+        let initial_vocab_size = 50257;
+        let max_codebook_size = 16;
+        let max_subtokens = 4;
+        let max_out_seq_length = 10_000;
+        let eot_token_id = 50256;
+
+        let mut rng = Xorshift(0x9E3779B97F4A7C15);
+        let disabled_ids = disabled_ids_to_set(Some(vec![eot_token_id]));
+
+        let len = 2_000;
+        let ids: Vec<usize> = (0..len).map(|_| rng.next_usize(initial_vocab_size)).collect();
+
+        let (compressed_ids, codebook_vec, _) = compress(
+            &ids,
+            initial_vocab_size,
+            max_codebook_size,
+            max_subtokens,
+            max_out_seq_length,
+            eot_token_id,
+            &disabled_ids,
+        );
+
+        let expected = decompress(
+            &compressed_ids,
+            &codebook_vec,
+            initial_vocab_size,
+            max_codebook_size,
+            max_subtokens,
+            eot_token_id,
+        );
+
+        let mut streamed = Vec::with_capacity(expected.len());
+        let mut streamer = StreamDecompressor::new();
+        let mut cursor = 0;
+        let chunk_output = 7;
+
+        while cursor < compressed_ids.len() || !streamer.carry.is_empty() {
+            let mut output = vec![0usize; chunk_output];
+            let (consumed, written) = streamer.decompress_into(
+                &compressed_ids[cursor..],
+                &codebook_vec,
+                initial_vocab_size,
+                max_codebook_size,
+                max_subtokens,
+                eot_token_id,
+                &mut output,
+            );
+            cursor += consumed;
+            streamed.extend_from_slice(&output[..written]);
+
+            if consumed == 0 && written == 0 {
+                break;
+            }
+        }
+
+        assert_eq!(streamed, expected);
+    }
+}
@@ -1,12 +1,132 @@
 use clap::Parser;
+use crc32fast::Hasher;
 use fastset::Set;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
 use itertools::Itertools;
+use rayon::prelude::*;
 use rustc_hash::FxHashMap;
-use std::cmp::min;
 use std::fs::File;
 use std::io::{BufReader, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use tqdm::pbar;
 
+const HEADER_SIZE: usize = 256 * 4;
+const COMPRESSED_HEADER_VERSION: i32 = 2;
+// XORed into every window's CRC32 so a checksum from this format can never collide
+// with a checksum computed over the same bytes for an unrelated purpose.
+const CHECKSUM_DOMAIN_CONST: u32 = 0x5A5A_5A5A;
+
+// Size of each block handed to the disk codec. Fixed so a reader can jump straight to
+// block `i` using only the block-offset table, without decoding earlier blocks.
+const DISK_BLOCK_SIZE: usize = 1 << 16;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DiskCodec {
+    None,
+    Lz4,
+    Deflate,
+}
+
+impl DiskCodec {
+    fn id(self) -> u32 {
+        match self {
+            DiskCodec::None => 0,
+            DiskCodec::Lz4 => 1,
+            DiskCodec::Deflate => 2,
+        }
+    }
+
+    fn from_id(id: u32) -> Self {
+        match id {
+            0 => DiskCodec::None,
+            1 => DiskCodec::Lz4,
+            2 => DiskCodec::Deflate,
+            other => panic!("unknown disk codec id {}", other),
+        }
+    }
+
+    fn compress_block(self, block: &[u8]) -> Vec<u8> {
+        match self {
+            DiskCodec::None => block.to_vec(),
+            DiskCodec::Lz4 => lz4_flex::compress(block),
+            DiskCodec::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(block).unwrap();
+                encoder.finish().unwrap()
+            }
+        }
+    }
+
+    fn decompress_block(self, block: &[u8], original_len: usize) -> Vec<u8> {
+        match self {
+            DiskCodec::None => block.to_vec(),
+            DiskCodec::Lz4 => lz4_flex::decompress(block, original_len).unwrap(),
+            DiskCodec::Deflate => {
+                let mut decoder = DeflateDecoder::new(block);
+                let mut out = Vec::with_capacity(original_len);
+                decoder.read_to_end(&mut out).unwrap();
+                out
+            }
+        }
+    }
+}
+
+/// One block-table entry: how many bytes the block takes on disk and how many bytes
+/// it expands back to.
+struct BlockEntry {
+    stored_len: u32,
+    original_len: u32,
+}
+
+/// Splits `data` into `DISK_BLOCK_SIZE` chunks, runs each through `codec`, and writes
+/// the blocks followed by their offset table (so a reader can seek to any block
+/// without inflating the ones before it). Returns the number of bytes written.
+fn write_blocked(writer: &mut impl Write, data: &[u8], codec: DiskCodec) -> usize {
+    let mut entries = Vec::new();
+    let mut bytes_written = 0;
+
+    for chunk in data.chunks(DISK_BLOCK_SIZE) {
+        let stored = codec.compress_block(chunk);
+        writer.write_all(&stored).unwrap();
+        bytes_written += stored.len();
+        entries.push(BlockEntry {
+            stored_len: stored.len() as u32,
+            original_len: chunk.len() as u32,
+        });
+    }
+
+    let mut table_bytes = Vec::with_capacity(4 + entries.len() * 8);
+    for entry in &entries {
+        table_bytes.extend(entry.stored_len.to_le_bytes());
+        table_bytes.extend(entry.original_len.to_le_bytes());
+    }
+    // Block count goes last, so a reader can locate the table from the region's end.
+    table_bytes.extend((entries.len() as u32).to_le_bytes());
+    writer.write_all(&table_bytes).unwrap();
+    bytes_written + table_bytes.len()
+}
+
+/// Inverse of `write_blocked`: given the full blocked region (data blocks followed by
+/// the offset table), reconstructs the original byte buffer.
+fn read_blocked(region: &[u8], codec: DiskCodec) -> Vec<u8> {
+    let num_blocks = u32::from_le_bytes(region[region.len() - 4..].try_into().unwrap()) as usize;
+    let table_len = 4 + num_blocks * 8;
+    let table = &region[region.len() - table_len..region.len() - 4];
+
+    let mut out = Vec::new();
+    let mut offset = 0;
+    for entry in table.chunks_exact(8) {
+        let stored_len = u32::from_le_bytes(entry[0..4].try_into().unwrap()) as usize;
+        let original_len = u32::from_le_bytes(entry[4..8].try_into().unwrap()) as usize;
+        let block = &region[offset..offset + stored_len];
+        out.extend(codec.decompress_block(block, original_len));
+        offset += stored_len;
+    }
+
+    out
+}
+
 #[derive(Parser, Debug)]
 struct Args {
     #[arg(short, long, default_value = "fineweb10B")]
@@ -23,6 +143,24 @@ struct Args {
     max_out_seq_length: usize,
     #[arg(long, default_value = "50256")]
     eot_token_id: usize,
+    /// Instead of compressing, re-read each already-produced `compressed_*` file and
+    /// validate its per-window CRC32 checksums.
+    #[arg(long)]
+    verify: bool,
+    /// Block-level codec applied to the `compressed_*`/`codebooks_*` output files on
+    /// top of the token-level dictionary compression.
+    #[arg(long, value_enum, default_value = "none")]
+    disk_codec: DiskCodec,
+}
+
+/// CRC32 of a window's `compressed_ids`, as they are written on disk (u16 little-endian),
+/// XORed with `CHECKSUM_DOMAIN_CONST`.
+fn window_checksum(window: &[usize]) -> u32 {
+    let bytes: Vec<u8> = window.iter().flat_map(|&x| (x as u16).to_le_bytes()).collect();
+
+    let mut hasher = Hasher::new();
+    hasher.update(&bytes);
+    hasher.finalize() ^ CHECKSUM_DOMAIN_CONST
 }
 
 #[inline(always)]
@@ -61,6 +199,52 @@ fn disabled_ids_to_set(disabled_ids: Option<Vec<usize>>) -> Set {
     )
 }
 
+// Raw bytes pulled from the shard per read, decoded into ids on the fly, so only one
+// chunk of raw bytes is ever resident rather than the whole shard's worth.
+const INPUT_CHUNK_BYTES: usize = 1 << 20;
+
+/// Reads the token ids following the header, decoding `u16` little-endian pairs as
+/// they arrive instead of buffering the whole shard as raw bytes first (`read_to_end`
+/// plus a `collect` meant two full-shard-sized buffers were resident at once: a ~2
+/// byte/token raw `Vec<u8>` and an 8 byte/token decoded `ids`, ~10 bytes/token total).
+/// This removes the raw-byte copy, which brings peak memory on the input side down to
+/// ~8 bytes/token - a ~20% cut, not a shard-size-independent bound. `ids` below is
+/// still sized to the whole shard up front and is the dominant cost: `document_ranges`/
+/// `compress_range` (chunk0-2) need random access across the full token stream for
+/// their per-chunk windowing, which is incompatible with discarding decoded tokens as
+/// windows are flushed. Genuinely bounding memory on multi-GB shards - the original
+/// motivation for this change - needs that windowing pipeline reworked to stream, not
+/// just this function; tracked as a known gap rather than fixed here. A single
+/// leftover byte can straddle two reads when a chunk boundary lands in the middle of a
+/// pair; it is carried over to the next chunk, mirroring how `StreamDecompressor`
+/// carries a partial codebook entry across calls.
+fn read_ids(reader: &mut impl Read, num_tokens: usize) -> Vec<usize> {
+    let mut ids = Vec::with_capacity(num_tokens);
+    let mut chunk = vec![0u8; INPUT_CHUNK_BYTES];
+    let mut carry: Option<u8> = None;
+
+    loop {
+        let bytes_read = reader.read(&mut chunk).unwrap();
+        if bytes_read == 0 {
+            break;
+        }
+
+        let mut bytes = &chunk[..bytes_read];
+        if let Some(lead) = carry.take() {
+            ids.push(u16::from_le_bytes([lead, bytes[0]]) as usize);
+            bytes = &bytes[1..];
+        }
+
+        let mut pairs = bytes.chunks_exact(2);
+        ids.extend(pairs.by_ref().map(|pair| u16::from_le_bytes([pair[0], pair[1]]) as usize));
+        if let [last] = pairs.remainder() {
+            carry = Some(*last);
+        }
+    }
+
+    ids
+}
+
 #[inline(always)]
 fn push_to_compressed_ids(compressed_ids: &mut Vec<usize>, id: usize, max_out_seq_length: usize) {
     if compressed_ids.len() < max_out_seq_length {
@@ -172,6 +356,98 @@ fn compress(
     (compressed_ids, codebook_vec, i)
 }
 
+/// Splits the token stream into per-document ranges at `eot_token_id` boundaries.
+fn document_ranges(ids: &[usize], num_tokens: usize, eot_token_id: usize) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+
+    for i in 1..num_tokens {
+        if ids[i] == eot_token_id {
+            ranges.push((start, i));
+            start = i;
+        }
+    }
+    if start < num_tokens {
+        ranges.push((start, num_tokens));
+    }
+
+    ranges
+}
+
+/// Groups consecutive whole documents into contiguous chunks of at least
+/// `target_tokens` tokens each (the last chunk may be shorter). Chunks are never
+/// split mid-document, but unlike one-document-per-chunk, a document shorter than
+/// `target_tokens` is merged with its neighbours instead of being processed alone -
+/// that's what lets `compress_range` pack a short document's tail into the next
+/// document's window the same way the pre-parallel, whole-file loop did.
+fn chunk_document_ranges(doc_ranges: &[(usize, usize)], target_tokens: usize) -> Vec<(usize, usize)> {
+    let mut chunks = Vec::new();
+    let mut chunk_start = None;
+    let mut chunk_tokens = 0;
+
+    for &(doc_start, doc_end) in doc_ranges {
+        if chunk_start.is_none() {
+            chunk_start = Some(doc_start);
+        }
+        chunk_tokens += doc_end - doc_start;
+
+        if chunk_tokens >= target_tokens {
+            chunks.push((chunk_start.unwrap(), doc_end));
+            chunk_start = None;
+            chunk_tokens = 0;
+        }
+    }
+    if let Some(start) = chunk_start {
+        chunks.push((start, doc_ranges.last().unwrap().1));
+    }
+
+    chunks
+}
+
+/// Compresses a contiguous range of the token stream with the same windowing the
+/// pre-parallel `compress_file` loop used on the whole file: windows advance continuously
+/// across document boundaries inside the range, so a document's trailing tokens are
+/// packed into the next document's window instead of being dropped. Windowing stops
+/// once at most `max_out_seq_length` tokens of the range remain, or at the first window
+/// that runs out of input before holding `max_out_seq_length` ids; the tokens from that
+/// point to `end` are left uncompressed - one such remainder per chunk instead of one
+/// per file.
+fn compress_range(
+    ids: &[usize],
+    start: usize,
+    end: usize,
+    args: &Args,
+    disabled_ids: &Set,
+) -> (Vec<usize>, Vec<usize>) {
+    let mut compressed_ids = Vec::new();
+    let mut codebook_vec = Vec::new();
+
+    let mut i = start;
+    while i < end && (end - i) > args.max_out_seq_length {
+        let (c_ids, c_codebook, remaining_ids_offset) = compress(
+            ids,
+            i,
+            end,
+            args.initial_vocab_size,
+            args.max_codebook_size,
+            args.max_subtokens,
+            args.max_out_seq_length,
+            args.eot_token_id,
+            disabled_ids,
+        );
+
+        if c_ids.len() != args.max_out_seq_length {
+            break;
+        }
+
+        i += remaining_ids_offset;
+        compressed_ids.extend(c_ids);
+        codebook_vec.extend(c_codebook);
+    }
+
+    (compressed_ids, codebook_vec)
+}
+
 fn compress_file(filename: &str, args: &Args) {
     let file = File::open(format!("../{}/{}", args.name, filename)).unwrap();
     let mut reader = BufReader::new(file);
@@ -183,14 +459,6 @@ fn compress_file(filename: &str, args: &Args) {
         .map(|chunk| i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
         .collect();
 
-    let mut ids_buffer = Vec::new();
-    reader.read_to_end(&mut ids_buffer).unwrap();
-
-    let ids: Vec<usize> = ids_buffer
-        .chunks_exact(2)
-        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]) as usize)
-        .collect();
-
     assert!(
         header[0] == 20240520,
         "magic number mismatch in the data .bin file"
@@ -198,73 +466,354 @@ fn compress_file(filename: &str, args: &Args) {
     assert!(header[1] == 1, "unsupported version");
     let num_tokens = header[2] as usize;
 
+    let ids = read_ids(&mut reader, num_tokens);
+
     let disabled_ids = disabled_ids_to_set(Some(vec![args.eot_token_id]));
 
-    let mut compressed_ids: Vec<usize> = Vec::new();
-    let mut codebook_vec: Vec<usize> = Vec::new();
+    let doc_ranges = document_ranges(&ids, num_tokens, args.eot_token_id);
+
+    // Target enough chunks to keep every thread busy, but each chunk spans many
+    // contiguous documents (grouped until it holds target_tokens), not just one - a
+    // document shorter than max_out_seq_length still gets its tail packed with its
+    // neighbours' tokens instead of being windowed (and dropped) on its own.
+    let chunk_count = rayon::current_num_threads() * 4;
+    let target_tokens = num_tokens.div_ceil(chunk_count).max(args.max_out_seq_length);
+    let chunks = chunk_document_ranges(&doc_ranges, target_tokens);
 
-    let mut i: usize = 0;
     let mut pb = pbar(Some(num_tokens));
-    while i < num_tokens && (num_tokens - i) > args.max_out_seq_length {
-        let (c_ids, c_codebook, remaining_ids_offset) = compress(
-            &ids,
-            i,
-            num_tokens,
-            args.initial_vocab_size,
-            args.max_codebook_size,
-            args.max_subtokens,
-            args.max_out_seq_length,
-            args.eot_token_id,
-            &disabled_ids,
-        );
-        let _ = pb.update(min(remaining_ids_offset, num_tokens - i));
-        i += remaining_ids_offset;
+    let progress = AtomicUsize::new(0);
+
+    let results: Vec<(Vec<usize>, Vec<usize>)> = chunks
+        .into_par_iter()
+        .map(|(start, end)| {
+            let result = compress_range(&ids, start, end, args, &disabled_ids);
+            progress.fetch_add(end - start, Ordering::Relaxed);
+            result
+        })
+        .collect();
 
-        if c_ids.len() != args.max_out_seq_length {
-            println!("c_ids.len(): {}", c_ids.len());
-            return;
-        }
+    let _ = pb.update(progress.load(Ordering::Relaxed));
+    let _ = pb.close();
 
+    // `chunks` is already in increasing-offset (i.e. original file) order, and
+    // `into_par_iter().collect()` on a Vec preserves that order regardless of which
+    // chunk finishes first, so no re-sorting by a chunk index is needed here.
+    let mut compressed_ids: Vec<usize> = Vec::new();
+    let mut codebook_vec: Vec<usize> = Vec::new();
+    for (c_ids, c_codebook) in results {
         compressed_ids.extend(c_ids);
         codebook_vec.extend(c_codebook);
     }
-    let _ = pb.close();
 
     println!("compressed_ids.len(): {}", compressed_ids.len());
     println!("codebook_vec.len(): {}", codebook_vec.len());
 
+    let checksums: Vec<u32> = compressed_ids
+        .chunks(args.max_out_seq_length)
+        .map(window_checksum)
+        .collect();
+
+    let compressed_ids_bytes: Vec<u8> = compressed_ids
+        .iter()
+        .flat_map(|&x| (x as u16).to_le_bytes())
+        .collect();
+    let ids_region: Vec<u8> = if args.disk_codec == DiskCodec::None {
+        compressed_ids_bytes
+    } else {
+        let mut region = Vec::new();
+        write_blocked(&mut region, &compressed_ids_bytes, args.disk_codec);
+        region
+    };
+    let checksum_offset = HEADER_SIZE + ids_region.len();
+
+    header[1] = COMPRESSED_HEADER_VERSION;
     header[2] = compressed_ids.len() as i32;
     header[3] = (codebook_vec.len() / (args.max_codebook_size * args.max_subtokens)) as i32;
     header[4] = args.max_codebook_size as i32;
     header[5] = args.max_subtokens as i32;
+    header[6] = checksum_offset as i32;
+    header[7] = checksums.len() as i32;
+    header[8] = args.disk_codec.id() as i32;
+    header[9] = args.max_out_seq_length as i32;
 
     let mut compressed_file =
         File::create(format!("../{}/compressed_{}", args.name, filename)).unwrap();
     let header_bytes: Vec<u8> = header.iter().flat_map(|&x| x.to_le_bytes()).collect();
     compressed_file.write_all(&header_bytes).unwrap();
-    let compressed_ids_bytes: Vec<u8> = compressed_ids
-        .iter()
-        .flat_map(|&x| (x as u16).to_le_bytes())
-        .collect();
-    compressed_file.write_all(&compressed_ids_bytes).unwrap();
-
+    compressed_file.write_all(&ids_region).unwrap();
+    let checksum_bytes: Vec<u8> = checksums.iter().flat_map(|&c| c.to_le_bytes()).collect();
+    compressed_file.write_all(&checksum_bytes).unwrap();
+
+    // TODO(data-loading owner): nothing in this binary reads codebooks_* back, even
+    // for disk_codec == None, so this layout (and, when disk_codec != None, the
+    // block table plus the trailing codec id/original-length footer below) is only
+    // exercised by this file's own write_blocked/read_blocked unit tests - confirm it
+    // still matches what the Python-side loader actually expects before shipping a
+    // disk_codec change, since there's no Rust-side consumer here to catch a mismatch.
     let mut codebook_file =
         File::create(format!("../{}/codebooks_{}", args.name, filename)).unwrap();
     let codebook_bytes: Vec<u8> = codebook_vec
         .iter()
         .flat_map(|&x| (x as u16).to_le_bytes())
         .collect();
-    codebook_file.write_all(&codebook_bytes).unwrap();
+    if args.disk_codec == DiskCodec::None {
+        codebook_file.write_all(&codebook_bytes).unwrap();
+    } else {
+        write_blocked(&mut codebook_file, &codebook_bytes, args.disk_codec);
+        // Trailing footer so a reader that only has the file (no external args) can
+        // still tell which codec produced it and how large the decoded buffer is.
+        codebook_file
+            .write_all(&args.disk_codec.id().to_le_bytes())
+            .unwrap();
+        codebook_file
+            .write_all(&(codebook_bytes.len() as u32).to_le_bytes())
+            .unwrap();
+    }
+}
+
+/// Re-reads a `compressed_*` file written by `compress_file` and recomputes every
+/// window's CRC32, reporting the first mismatch it finds. Files written before the
+/// version-2 header (no checksum index) are reported as such rather than failing.
+/// Window boundaries come from the header's own `max_out_seq_length` (header[9]),
+/// not from `args`, so verification doesn't silently misalign if `--verify` is run
+/// with different flags than the file was compressed with.
+fn verify_file(filename: &str, args: &Args) {
+    let path = format!("../{}/compressed_{}", args.name, filename);
+    let file = File::open(&path).unwrap();
+    let mut reader = BufReader::new(file);
+
+    let mut header_buffer = vec![0u8; HEADER_SIZE];
+    reader.read_exact(&mut header_buffer).unwrap();
+    let header: Vec<i32> = header_buffer
+        .chunks_exact(4)
+        .map(|chunk| i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+
+    assert!(
+        header[0] == 20240520,
+        "magic number mismatch in {}",
+        path
+    );
+    assert!(
+        header[1] == 1 || header[1] == COMPRESSED_HEADER_VERSION,
+        "unsupported compressed header version {} in {}",
+        header[1],
+        path
+    );
+
+    if header[1] == 1 {
+        println!("{}: version 1 header, no checksum index to verify", path);
+        return;
+    }
+
+    let num_compressed_ids = header[2] as usize;
+    let checksum_offset = header[6] as usize;
+    let num_windows = header[7] as usize;
+    let disk_codec = DiskCodec::from_id(header[8] as u32);
+    let max_out_seq_length = header[9] as usize;
+
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body).unwrap();
+
+    let ids_region = &body[..checksum_offset - HEADER_SIZE];
+    let compressed_ids_bytes = if disk_codec == DiskCodec::None {
+        ids_region.to_vec()
+    } else {
+        read_blocked(ids_region, disk_codec)
+    };
+
+    let compressed_ids: Vec<usize> = compressed_ids_bytes[..num_compressed_ids * 2]
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]) as usize)
+        .collect();
+
+    let checksum_bytes = &body[checksum_offset - HEADER_SIZE..];
+    let stored_checksums: Vec<u32> = checksum_bytes
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+    assert!(
+        stored_checksums.len() == num_windows,
+        "checksum index in {} is truncated",
+        path
+    );
+
+    for (window_idx, (window, &stored)) in compressed_ids
+        .chunks(max_out_seq_length)
+        .zip(stored_checksums.iter())
+        .enumerate()
+    {
+        let actual = window_checksum(window);
+        if actual != stored {
+            println!(
+                "{}: checksum mismatch in window {} (stored {:#010x}, recomputed {:#010x})",
+                path, window_idx, stored, actual
+            );
+            return;
+        }
+    }
+
+    println!("{}: all {} windows verified", path, num_windows);
 }
 
 fn main() {
     let args = Args::parse();
 
+    let process_file: fn(&str, &Args) = if args.verify { verify_file } else { compress_file };
+
     let mut filename = format!("fineweb_val_{:06}.bin", 0);
-    compress_file(&filename, &args);
+    process_file(&filename, &args);
 
     for chunk in 1..args.num_chunks + 1 {
         filename = format!("fineweb_train_{:06}.bin", chunk);
-        compress_file(&filename, &args);
+        process_file(&filename, &args);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_args(max_out_seq_length: usize) -> Args {
+        Args {
+            name: "test".to_string(),
+            num_chunks: 1,
+            initial_vocab_size: 1000,
+            max_codebook_size: 8,
+            // max_subtokens = 1 disables merging entirely (every `ids_to_merge` group
+            // is flushed as soon as it holds one token), so each call's output length
+            // is its consumed input length, plus one for the leading eot `compress`
+            // inserts when a window does not start on one, and totals are easy to reason about.
+            max_subtokens: 1,
+            max_out_seq_length,
+            eot_token_id: 0,
+            verify: false,
+            disk_codec: DiskCodec::None,
+        }
+    }
+
+    /// `num_docs` documents, each `doc_len` tokens long including its leading eot
+    /// marker, using ids below `initial_vocab_size` so none of them look like
+    /// codebook references.
+    fn synthetic_corpus(num_docs: usize, doc_len: usize, eot_token_id: usize) -> Vec<usize> {
+        let mut ids = Vec::new();
+        for _ in 0..num_docs {
+            ids.push(eot_token_id);
+            for t in 1..doc_len {
+                ids.push(eot_token_id + 1 + (t % 50));
+            }
+        }
+        ids
+    }
+
+    #[test]
+    fn chunking_recovers_tokens_the_broken_per_document_split_dropped() {
+        // Every document (8 tokens) is far shorter than max_out_seq_length (1024) on
+        // its own. Windowing each document independently against that bound - what the
+        // first parallel cut over documents did - never enters `compress_range`'s loop
+        // body for any of them, so it contributes zero output across the whole corpus.
+        // Grouping documents into token-budget chunks before windowing must recover that.
+        let args = test_args(1024);
+        let disabled_ids = disabled_ids_to_set(Some(vec![args.eot_token_id]));
+
+        let ids = synthetic_corpus(400, 8, args.eot_token_id);
+        let num_tokens = ids.len();
+        let doc_ranges = document_ranges(&ids, num_tokens, args.eot_token_id);
+
+        let per_document_total: usize = doc_ranges
+            .iter()
+            .map(|&(start, end)| compress_range(&ids, start, end, &args, &disabled_ids).0.len())
+            .sum();
+
+        let target_tokens = num_tokens.div_ceil(3).max(args.max_out_seq_length);
+        let chunks = chunk_document_ranges(&doc_ranges, target_tokens);
+        let chunked_total: usize = chunks
+            .iter()
+            .map(|&(start, end)| compress_range(&ids, start, end, &args, &disabled_ids).0.len())
+            .sum();
+
+        assert_eq!(
+            per_document_total, 0,
+            "sanity check: every synthetic document is shorter than max_out_seq_length"
+        );
+        assert!(
+            chunked_total > 0,
+            "grouping documents into chunks must recover the tokens the old per-document split silently dropped"
+        );
+    }
+
+    #[test]
+    fn chunk_document_ranges_never_splits_a_document() {
+        let eot = 0;
+        let ids = synthetic_corpus(6, 7, eot);
+        let doc_ranges = document_ranges(&ids, ids.len(), eot);
+
+        let chunks = chunk_document_ranges(&doc_ranges, 10);
+
+        let doc_starts: std::collections::HashSet<usize> =
+            doc_ranges.iter().map(|&(s, _)| s).collect();
+        for &(start, _) in &chunks {
+            assert!(
+                doc_starts.contains(&start),
+                "chunk must start exactly at a document boundary"
+            );
+        }
+        assert_eq!(chunks.last().unwrap().1, ids.len());
+    }
+
+    #[test]
+    fn blocked_round_trip_preserves_data_for_every_disk_codec() {
+        // Deliberately not a multiple of DISK_BLOCK_SIZE, so the last block exercises
+        // the short-final-chunk path through both write_blocked and read_blocked.
+        let data: Vec<u8> = (0..DISK_BLOCK_SIZE + 1234).map(|i| (i % 251) as u8).collect();
+
+        for codec in [DiskCodec::None, DiskCodec::Lz4, DiskCodec::Deflate] {
+            let mut region = Vec::new();
+            write_blocked(&mut region, &data, codec);
+            let restored = read_blocked(&region, codec);
+            assert_eq!(restored, data, "round trip mismatch for {:?}", codec);
+        }
+    }
+
+    #[test]
+    fn blocked_round_trip_survives_the_codebook_file_footer() {
+        // `codebooks_*` files append an 8-byte footer (codec id, then original length)
+        // after write_blocked's own table, so a reader has to skip it before handing
+        // the region to read_blocked. Nothing in this binary reads codebooks_* back
+        // yet, but this pins down that the footer doesn't corrupt the blocked layout
+        // underneath it and that the footer's own bytes round-trip correctly.
+        let codebook_bytes: Vec<u8> = (0..5000u32).flat_map(|x| (x as u16).to_le_bytes()).collect();
+        let codec = DiskCodec::Deflate;
+
+        let mut codebook_file = Vec::new();
+        write_blocked(&mut codebook_file, &codebook_bytes, codec);
+        codebook_file.extend(codec.id().to_le_bytes());
+        codebook_file.extend((codebook_bytes.len() as u32).to_le_bytes());
+
+        let footer_start = codebook_file.len() - 8;
+        let (region, footer) = codebook_file.split_at(footer_start);
+        let footer_codec = DiskCodec::from_id(u32::from_le_bytes(footer[0..4].try_into().unwrap()));
+        let footer_original_len = u32::from_le_bytes(footer[4..8].try_into().unwrap()) as usize;
+
+        assert_eq!(footer_codec, codec);
+        assert_eq!(footer_original_len, codebook_bytes.len());
+        assert_eq!(read_blocked(region, footer_codec), codebook_bytes);
+    }
+
+    #[test]
+    fn window_checksum_is_domain_separated_and_order_sensitive() {
+        let window = [1usize, 2, 3, 4];
+        let reordered = [4usize, 3, 2, 1];
+
+        let bytes: Vec<u8> = window.iter().flat_map(|&x| (x as u16).to_le_bytes()).collect();
+        let mut hasher = Hasher::new();
+        hasher.update(&bytes);
+        let raw_crc32 = hasher.finalize();
+
+        // The stored checksum is the bare CRC32 of the same bytes XORed with
+        // CHECKSUM_DOMAIN_CONST, so it never equals a plain CRC32 computed over those
+        // bytes for another purpose.
+        assert_eq!(window_checksum(&window), raw_crc32 ^ CHECKSUM_DOMAIN_CONST);
+        assert_ne!(window_checksum(&window), window_checksum(&reordered));
     }
 }